@@ -0,0 +1,62 @@
+// Per-entry compression codec for WAL records.
+//
+// Gated behind the `zstd` cargo feature: when enabled, `append` may
+// compress a record's data before writing it, recording which codec was
+// used so `load`/`read_all` can transparently reverse it. A codec tag of 0
+// ("stored") means the bytes are written verbatim — used whenever
+// compression isn't enabled, or when compressing a record didn't actually
+// shrink it — so old, uncompressed logs stay readable either way.
+
+use std::io;
+
+pub const STORED: u8 = 0;
+pub const ZSTD: u8 = 1;
+
+/// Picks a codec for `data` and returns `(codec tag, bytes to write)`.
+pub fn encode(data: &[u8]) -> (u8, Vec<u8>) {
+    #[cfg(feature = "zstd")]
+    {
+        if let Ok(compressed) = zstd::stream::encode_all(data, 0) {
+            if compressed.len() < data.len() {
+                return (ZSTD, compressed);
+            }
+        }
+    }
+    (STORED, data.to_vec())
+}
+
+/// Reverses `encode`, given the codec tag and the original (uncompressed)
+/// length recorded alongside it.
+pub fn decode(codec: u8, payload: &[u8], orig_len: usize) -> io::Result<Vec<u8>> {
+    match codec {
+        STORED => {
+            if payload.len() != orig_len {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "stored WAL record length doesn't match its header",
+                ));
+            }
+            Ok(payload.to_vec())
+        }
+        ZSTD => decode_zstd(payload, orig_len),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unknown WAL codec tag",
+        )),
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn decode_zstd(payload: &[u8], orig_len: usize) -> io::Result<Vec<u8>> {
+    let mut out = zstd::stream::decode_all(payload)?;
+    out.truncate(orig_len);
+    Ok(out)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decode_zstd(_payload: &[u8], _orig_len: usize) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "WAL record is zstd-compressed but the `zstd` feature is disabled",
+    ))
+}
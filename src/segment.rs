@@ -0,0 +1,346 @@
+// Segmented storage backend: instead of one unbounded file, the log is
+// split across numbered segment files (`0000000001.wal`, `0000000002.wal`,
+// ...) that roll over once a write would take the active segment past a
+// configurable size threshold. This bounds any single file's size and lets checkpointing
+// delete whole retired segments instead of zeroing one giant file.
+//
+// `SegmentedStore` implements `WALStore` by presenting the segments as one
+// contiguous virtual offset space, so `WAL<SegmentedStore>` gets append,
+// recovery and torn-write handling for free from the existing generic code.
+
+use crate::store::WALStore;
+use regex::Regex;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// Segment files are named with a fixed-width, zero-padded sequence number
+// (matching the `\d{10}` pattern in `open`'s regex) so a plain lexicographic
+// sort also sorts them in creation order.
+fn segment_file_name(seq: u64) -> String {
+    format!("{seq:010}.wal")
+}
+
+// A sidecar file recording how many bytes have been permanently dropped by
+// `drop_segments_before` over the directory's lifetime. Without this, a
+// restart would recompute base offsets from 0 over whatever segments
+// happen to remain on disk, so offsets handed out before a checkpoint could
+// collide with offsets handed out after a restart. The content is just the
+// cumulative dropped byte count as an 8-byte big-endian integer; it's
+// rewritten via a temp-file-then-rename so a crash mid-write can't corrupt it.
+const BASE_FILE: &str = ".wal_base";
+
+fn read_persisted_base(dir: &Path) -> io::Result<u64> {
+    match fs::read(dir.join(BASE_FILE)) {
+        Ok(bytes) if bytes.len() == 8 => Ok(u64::from_be_bytes(bytes.try_into().unwrap())),
+        Ok(_) => Ok(0), // unexpected sidecar contents; don't fail the whole open
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+fn write_persisted_base(dir: &Path, base: u64) -> io::Result<()> {
+    let tmp = dir.join(".wal_base.tmp");
+    fs::write(&tmp, base.to_be_bytes())?;
+    fs::rename(&tmp, dir.join(BASE_FILE))
+}
+
+struct Segment {
+    seq: u64,
+    file: File,
+    base_offset: u64, // virtual offset where this segment's data starts
+    len: u64,         // bytes written into this segment so far
+}
+
+pub struct SegmentedStore {
+    dir: PathBuf,
+    file_nbit: u64, // segment rolls once it would exceed 2^file_nbit bytes
+    segments: Vec<Segment>,
+}
+
+impl SegmentedStore {
+    // Scans `dir` for existing segment files (ignoring anything that doesn't
+    // match the naming scheme), opens them in sequence order, and starts a
+    // fresh first segment if the directory is empty.
+    pub fn open<P: AsRef<Path>>(dir: P, file_nbit: u64) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let name_re = Regex::new(r"^(\d{10})\.wal$").expect("valid regex");
+
+        let mut seqs: Vec<u64> = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if let Some(name) = entry.file_name().to_str() {
+                if let Some(caps) = name_re.captures(name) {
+                    if let Ok(seq) = caps[1].parse() {
+                        seqs.push(seq);
+                    }
+                }
+            }
+        }
+        seqs.sort_unstable();
+        if seqs.is_empty() {
+            seqs.push(1);
+        }
+
+        let mut segments = Vec::with_capacity(seqs.len());
+        let mut base_offset = read_persisted_base(&dir)?;
+        for seq in seqs {
+            let path = dir.join(segment_file_name(seq));
+            let file = OpenOptions::new()
+                .create(true)
+                .read(true)
+                .write(true)
+                .truncate(false)
+                .open(&path)?;
+            let len = file.metadata()?.len();
+            segments.push(Segment {
+                seq,
+                file,
+                base_offset,
+                len,
+            });
+            base_offset += len;
+        }
+
+        Ok(SegmentedStore {
+            dir,
+            file_nbit,
+            segments,
+        })
+    }
+
+    fn threshold(&self) -> u64 {
+        1u64 << self.file_nbit
+    }
+
+    fn total_len(&self) -> u64 {
+        self.segments
+            .last()
+            .map(|s| s.base_offset + s.len)
+            .unwrap_or(0)
+    }
+
+    fn roll_segment(&mut self) -> io::Result<()> {
+        let next_seq = self.segments.last().map(|s| s.seq + 1).unwrap_or(1);
+        let base_offset = self.total_len();
+        let path = self.dir.join(segment_file_name(next_seq));
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .open(&path)?;
+        self.segments.push(Segment {
+            seq: next_seq,
+            file,
+            base_offset,
+            len: 0,
+        });
+        Ok(())
+    }
+
+    fn segment_index_for(&self, offset: u64) -> Option<usize> {
+        self.segments
+            .iter()
+            .position(|s| offset >= s.base_offset && offset < s.base_offset + s.len)
+    }
+
+    // Deletes whole segments that end at or before `watermark`, leaving the
+    // segment that straddles (or follows) it untouched. Used by checkpoints:
+    // once everything in a segment has been applied to main storage, the
+    // whole file can go instead of zeroing one ever-growing log.
+    //
+    // The offset of whatever segment remains first is persisted to the
+    // `.wal_base` sidecar, so a later `open` picks up base offsets where
+    // this process left off instead of restarting the virtual offset space
+    // at 0 and handing out offsets that collide with ones already handed
+    // out (and possibly persisted by the caller) before this checkpoint.
+    pub fn drop_segments_before(&mut self, watermark: u64) -> io::Result<()> {
+        let mut new_base = None;
+        while self.segments.len() > 1 && self.segments[0].base_offset + self.segments[0].len <= watermark {
+            let seg = self.segments.remove(0);
+            fs::remove_file(self.dir.join(segment_file_name(seg.seq)))?;
+            new_base = Some(self.segments[0].base_offset);
+        }
+        if let Some(base) = new_base {
+            write_persisted_base(&self.dir, base)?;
+        }
+        Ok(())
+    }
+}
+
+impl WALStore for SegmentedStore {
+    fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        if len == 0 {
+            return Ok(Vec::new()); // e.g. an empty record's payload at the end of the log
+        }
+        let idx = self
+            .segment_index_for(offset)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        let seg = &mut self.segments[idx];
+        let local_offset = offset - seg.base_offset;
+        if local_offset + len as u64 > seg.len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        seg.file.seek(SeekFrom::Start(local_offset))?;
+        let mut buf = vec![0u8; len];
+        seg.file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn write_at(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        if offset != self.total_len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "SegmentedStore only supports sequential appends",
+            ));
+        }
+
+        let threshold = self.threshold();
+        let active_is_full = self
+            .segments
+            .last()
+            .is_some_and(|s| s.len > 0 && s.len + bytes.len() as u64 > threshold);
+        if active_is_full {
+            self.roll_segment()?;
+        }
+
+        let seg = self.segments.last_mut().expect("at least one segment");
+        seg.file.seek(SeekFrom::Start(seg.len))?;
+        seg.file.write_all(bytes)?;
+        seg.len += bytes.len() as u64;
+        Ok(())
+    }
+
+    // Syncs every segment, not just the active one: a batched flush can roll
+    // over mid-batch and leave unsynced records in an earlier segment.
+    fn sync(&mut self) -> io::Result<()> {
+        for seg in &mut self.segments {
+            seg.file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    // Shrinks the store to exactly `len` bytes: whole segments past it are
+    // deleted, and the segment straddling `len` is truncated in place. Used
+    // to drop a torn record left by a crash.
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        let keep_idx = self
+            .segments
+            .iter()
+            .position(|s| len <= s.base_offset + s.len)
+            .unwrap_or(self.segments.len().saturating_sub(1));
+
+        while self.segments.len() > keep_idx + 1 {
+            let seg = self.segments.pop().expect("index in bounds");
+            fs::remove_file(self.dir.join(segment_file_name(seg.seq)))?;
+        }
+
+        if let Some(seg) = self.segments.get_mut(keep_idx) {
+            let local_len = len - seg.base_offset;
+            seg.file.set_len(local_len)?;
+            seg.len = local_len;
+        }
+        Ok(())
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(self.total_len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WAL;
+
+    // Each test gets its own throwaway directory under the OS temp dir,
+    // named after the test so parallel `cargo test` runs don't collide.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wal_segment_test_{}_{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn rolls_over_and_recovers_entries_across_segments() {
+        let dir = test_dir("rollover");
+        let mut wal = WAL::open_dir(&dir, 6).unwrap(); // 64-byte segments force rotation
+
+        for _ in 0..6 {
+            wal.append(b"0123456789").unwrap();
+        }
+
+        let segment_files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|e| e.ok().map(|e| e.file_name().to_string_lossy().into_owned()))
+            .filter(|name| name.ends_with(".wal"))
+            .collect();
+        assert!(
+            segment_files.len() >= 2,
+            "expected rotation across multiple segment files, got {segment_files:?}"
+        );
+
+        let entries = wal.read_all().unwrap();
+        assert_eq!(entries.len(), 6);
+        assert!(entries.iter().all(|e| e == b"0123456789"));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn checkpoint_then_reopen_keeps_offsets_monotonic() {
+        let dir = test_dir("checkpoint_offsets");
+
+        let (offset_a, offset_b) = {
+            let mut wal = WAL::open_dir(&dir, 6).unwrap();
+            let offset_a = wal.append(&[b'a'; 51]).unwrap(); // fills segment 1 to the 64-byte threshold
+            let offset_b = wal.append(b"b").unwrap(); // rolls into segment 2
+            wal.checkpoint(offset_b).unwrap(); // drops segment 1 entirely
+            (offset_a, offset_b)
+        };
+        assert!(offset_b > offset_a);
+
+        let mut reopened = WAL::open_dir(&dir, 6).unwrap();
+        let offset_c = reopened.append(b"c").unwrap();
+        assert!(
+            offset_c > offset_b,
+            "offsets must stay monotonic across a checkpoint + restart, got offset_b={offset_b} offset_c={offset_c}"
+        );
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn batched_append_still_bounds_segment_size() {
+        let dir = test_dir("batched_append_bounds");
+        let mut wal = WAL::open_dir(&dir, 6).unwrap(); // 64-byte segments force rotation
+
+        // A single append_batch stages ten records behind one flush, so this
+        // regresses flush() handing the whole pending buffer to `write_at` in
+        // one call, which bypassed per-record segment rollover and let the
+        // entire 230-byte batch land in a single oversized segment file.
+        let entries: Vec<&[u8]> = vec![b"0123456789"; 10];
+        wal.append_batch(&entries).unwrap();
+
+        let max_segment_len = fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().ends_with(".wal"))
+            .map(|e| e.metadata().unwrap().len())
+            .max()
+            .unwrap();
+        assert!(
+            max_segment_len <= 64,
+            "a batched flush must still respect the segment size threshold, got a segment of {max_segment_len} bytes"
+        );
+
+        let recovered = wal.read_all().unwrap();
+        assert_eq!(recovered.len(), 10);
+        assert!(recovered.iter().all(|e| e == b"0123456789"));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}